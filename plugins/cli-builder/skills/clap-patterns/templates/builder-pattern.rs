@@ -5,11 +5,16 @@
 /// - Runtime configuration
 /// - Custom help templates
 /// - Complex validation logic
+/// - External plugin subcommands discovered on PATH (like git)
+/// - Repeatable options with a comma-separated env var fallback
 ///
 /// Note: Prefer derive macros unless you need this level of control.
+/// Runtime subcommand names need clap's `string` feature.
 
 use clap::{Arg, ArgAction, ArgMatches, Command};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 
 fn build_cli() -> Command {
     Command::new("advanced-cli")
@@ -63,22 +68,125 @@ fn build_cli() -> Command {
                 .short('t')
                 .long("tag")
                 .value_name("TAG")
-                .help("Tags to apply (can be specified multiple times)")
+                .help("Tags to apply (can be specified multiple times, or set ADVANCED_CLI_TAGS=a,b,c)")
                 .action(ArgAction::Append),
         )
 }
 
+/// Comma-separated fallback for `--tag` when no tag is given on the command line
+const TAGS_ENV: &str = "ADVANCED_CLI_TAGS";
+
+/// Split a comma-separated list, trimming items and dropping empty ones
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Values of a repeatable arg, or the list in `env_var` if the arg wasn't given
+///
+/// Flags replace the env list rather than adding to it, the same precedence
+/// clap uses for single-valued `env` args.
+fn values_or_env(matches: &ArgMatches, id: &str, env_var: &str) -> Vec<String> {
+    match matches.get_many::<String>(id) {
+        Some(values) => values.cloned().collect(),
+        None => std::env::var(env_var)
+            .map(|value| split_list(&value))
+            .unwrap_or_default(),
+    }
+}
+
+/// Find executables named `<prefix><name>` on `path_var`
+///
+/// Earlier PATH entries win when the same plugin name appears twice, matching
+/// how the shell resolves commands.
+fn discover_plugins(prefix: &str, path_var: &OsStr) -> BTreeMap<String, PathBuf> {
+    let mut plugins = BTreeMap::new();
+
+    for dir in std::env::split_paths(path_var) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name.to_str().and_then(|n| n.strip_prefix(prefix)) else {
+                continue;
+            };
+            if !name.is_empty() && is_executable(&entry.path()) {
+                plugins
+                    .entry(name.to_string())
+                    .or_insert_with(|| entry.path());
+            }
+        }
+    }
+
+    plugins
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Register each discovered plugin as a subcommand that forwards its args
+fn with_plugins(mut cmd: Command, plugins: &BTreeMap<String, PathBuf>) -> Command {
+    if plugins.is_empty() {
+        return cmd;
+    }
+
+    for (name, path) in plugins {
+        cmd = cmd.subcommand(
+            Command::new(name.clone())
+                .about(format!("External plugin ({})", path.display()))
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new("args")
+                        .num_args(0..)
+                        .trailing_var_arg(true)
+                        .allow_hyphen_values(true),
+                ),
+        );
+    }
+
+    // Plugins handle their own arguments, so the top-level requirements
+    // (like --input) shouldn't apply when one is invoked
+    cmd.subcommand_negates_reqs(true)
+}
+
+/// Run the plugin with the remaining args and exit with its status
+fn run_plugin(path: &Path, matches: &ArgMatches) -> ! {
+    let args: Vec<&String> = matches
+        .get_many::<String>("args")
+        .unwrap_or_default()
+        .collect();
+
+    match std::process::Command::new(path).args(args).status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => {
+            eprintln!("Error: failed to run plugin {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn process_args(matches: &ArgMatches) {
     let input = matches.get_one::<PathBuf>("input").unwrap();
     let output = matches.get_one::<PathBuf>("output");
     let verbose = matches.get_flag("verbose");
     let count = *matches.get_one::<usize>("count").unwrap();
     let format = matches.get_one::<String>("format").unwrap();
-    let tags: Vec<_> = matches
-        .get_many::<String>("tags")
-        .unwrap_or_default()
-        .map(|s| s.as_str())
-        .collect();
+    let tags = values_or_env(matches, "tags", TAGS_ENV);
 
     if verbose {
         println!("Configuration:");
@@ -102,7 +210,15 @@ fn process_args(matches: &ArgMatches) {
 }
 
 fn main() {
-    let matches = build_cli().get_matches();
+    let cli = build_cli();
+    let prefix = format!("{}-", cli.get_name());
+    let plugins = discover_plugins(&prefix, &std::env::var_os("PATH").unwrap_or_default());
+    let matches = with_plugins(cli, &plugins).get_matches();
+
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        run_plugin(&plugins[name], sub_matches);
+    }
+
     process_args(&matches);
 }
 
@@ -110,3 +226,7 @@ fn main() {
 //
 // cargo run -- -i input.txt -o output.json -v -c 20 -f yaml -t alpha -t beta
 // cargo run -- --input data.txt --format toml --tag important
+// ADVANCED_CLI_TAGS=alpha,beta cargo run -- -i input.txt   (tags from env)
+//
+// Plugins: an executable `advanced-cli-hello` on PATH becomes `advanced-cli hello`
+// cargo run -- hello --name world
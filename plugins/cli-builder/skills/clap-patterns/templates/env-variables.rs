@@ -5,9 +5,30 @@
 /// - Fallback to CLI arguments
 /// - Default values
 /// - Sensitive data handling (API keys, tokens)
+/// - Typed config file whose serde defaults mirror the CLI defaults
+/// - Flexible boolean env vars (1/0, yes/no, on/off, true/false)
+/// - Per-setting provenance report (--trace-config-source)
+/// - `${VAR}` / `${VAR:-default}` expansion in config file strings
+/// - Config piped on stdin with `--config -`
+/// - Reproducible runs that ignore env-backed options (--no-env)
+/// - `.env` skeleton generated from the env-backed options (--print-env-template)
+/// - JSON Schema of the config file for editors (--print-config-schema;
+///   needs `schemars` and `serde_json`)
+/// - Shell-quoted echo of the resolved invocation, secrets masked (--echo-command)
+/// - Related options grouped under their own `--help` heading
+///   (`help_heading = "Network"`); headings appear in first-use order
+///
+/// Precedence: CLI flag > environment variable > config file > default.
 
-use clap::Parser;
-use std::path::PathBuf;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(name = "envapp")]
@@ -26,60 +47,587 @@ struct Cli {
 
     /// Log level: debug, info, warn, error
     ///
-    /// Defaults to "info" if not provided via CLI or LOG_LEVEL env var.
-    #[arg(long, env = "LOG_LEVEL", default_value = "info")]
-    log_level: String,
+    /// Defaults to "info" if not provided via CLI, LOG_LEVEL env var or config.
+    #[arg(long, env = "LOG_LEVEL")]
+    log_level: Option<String>,
 
     /// Configuration file path
     ///
     /// Reads from CONFIG_FILE env var, or uses default if not specified.
+    /// Use `-` to read the config from stdin.
     #[arg(long, env = "CONFIG_FILE", default_value = "config.toml")]
     config: PathBuf,
 
-    /// Number of workers (default from env or 4)
-    #[arg(long, env = "WORKER_COUNT", default_value_t = 4)]
-    workers: usize,
+    /// Number of workers (default from env, config or 4)
+    #[arg(long, env = "WORKER_COUNT")]
+    workers: Option<usize>,
 
     /// Enable debug mode
     ///
-    /// Can be set via DEBUG=1 or --debug flag
-    #[arg(long, env = "DEBUG", value_parser = clap::value_parser!(bool))]
+    /// Can be set via --debug or DEBUG=1/yes/on (DEBUG=0/no/off disables)
+    #[arg(long, env = "DEBUG", value_parser = parse_flexible_bool)]
     debug: bool,
 
+    /// Host to bind to (default from env, config or 127.0.0.1)
+    #[arg(long, env = "HOST", help_heading = "Network")]
+    host: Option<String>,
+
+    /// Port to listen on (default from env, config or 8080)
+    #[arg(short, long, env = "PORT", help_heading = "Network")]
+    port: Option<u16>,
+
+    /// Print each resolved setting and where its value came from
+    #[arg(long)]
+    trace_config_source: bool,
+
+    /// Ignore the environment variables listed as [env: ...] above; only
+    /// flags, the config file and defaults apply
+    #[arg(long)]
+    no_env: bool,
+
+    /// Print the fully resolved command line to stderr before running
+    ///
+    /// Values from env vars, the config file and defaults are written out as
+    /// flags, so the line can be pasted to reproduce the run. The API key and
+    /// any password in the database URL are masked.
+    #[arg(long)]
+    echo_command: bool,
+
+    /// Print a commented .env template for the variables above and exit
+    ///
+    /// Secret values are never filled in.
+    #[arg(long)]
+    print_env_template: bool,
+
+    /// Print a JSON Schema for the config file and exit
+    ///
+    /// Point an editor at it to check config.toml as you type.
+    #[arg(long)]
+    print_config_schema: bool,
+}
+
+/// Whether flag `id` was given, checked with a lenient parse that doesn't
+/// stop on missing required options
+fn early_flag(id: &str) -> bool {
+    Cli::command()
+        .ignore_errors(true)
+        .try_get_matches()
+        .is_ok_and(|matches| matches!(matches.get_one::<bool>(id), Some(true)))
+}
+
+/// The parser, with every `env` fallback removed when `--no-env` is given
+///
+/// clap resolves env vars while parsing, so the flag is found with a
+/// lenient first pass before the real parse.
+fn command() -> clap::Command {
+    if early_flag("no_env") {
+        Cli::command().mut_args(|arg| arg.env(None))
+    } else {
+        Cli::command()
+    }
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+fn default_workers() -> usize {
+    4
+}
+
+/// Parse a boolean the way people write it in environment variables
+///
+/// Accepts 1/0, yes/no, on/off and true/false, case-insensitively.
+fn parse_flexible_bool(s: &str) -> Result<bool, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "1" | "yes" | "on" | "true" => Ok(true),
+        "0" | "no" | "off" | "false" => Ok(false),
+        _ => Err(format!(
+            "`{}` is not a boolean (expected 1/0, yes/no, on/off or true/false)",
+            s
+        )),
+    }
+}
+
+/// Where a resolved setting got its value
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+    Cli,
+    Env(String),
+    Config(String),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Cli => write!(f, "cli"),
+            Source::Env(var) => write!(f, "env:{}", var),
+            Source::Config(key) => write!(f, "config:{}", key),
+            Source::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// Settings read from the config file
+///
+/// Every field has a serde default equal to the CLI default, so a partial
+/// file only overrides the keys it names.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct Config {
+    /// Log level: debug, info, warn or error
+    #[serde(default = "default_log_level")]
+    log_level: String,
+
+    #[serde(default)]
+    server: ServerConfig,
+
+    /// Provenance of each setting, keyed by its dotted config path;
+    /// settings missing from the map kept their default
+    #[serde(skip)]
+    sources: BTreeMap<&'static str, Source>,
+}
+
+/// The `[server]` table of the config file
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ServerConfig {
     /// Host to bind to
-    #[arg(long, env = "HOST", default_value = "127.0.0.1")]
+    #[serde(default = "default_host")]
     host: String,
 
     /// Port to listen on
-    #[arg(short, long, env = "PORT", default_value_t = 8080)]
+    #[serde(default = "default_port")]
     port: u16,
+
+    /// Number of worker threads
+    #[serde(default = "default_workers")]
+    workers: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            log_level: default_log_level(),
+            server: ServerConfig::default(),
+            sources: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: default_host(),
+            port: default_port(),
+            workers: default_workers(),
+        }
+    }
+}
+
+impl Config {
+    /// Dotted paths of every setting that can come from the config file
+    const KEYS: [&'static str; 4] = ["log_level", "server.host", "server.port", "server.workers"];
+
+    /// Load the config file, falling back to defaults when it doesn't exist;
+    /// `-` reads it from stdin
+    fn load(path: &Path) -> Result<Config, String> {
+        if path == Path::new("-") {
+            return Self::read(io::stdin().lock(), "<stdin>");
+        }
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let file =
+            File::open(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::read(file, &path.display().to_string())
+    }
+
+    /// Parse config TOML from `input`, naming it `origin` in errors
+    fn read(mut input: impl Read, origin: &str) -> Result<Config, String> {
+        let mut text = String::new();
+        input
+            .read_to_string(&mut text)
+            .map_err(|e| format!("failed to read {}: {}", origin, e))?;
+        let invalid = |e: toml::de::Error| format!("invalid config {}: {}", origin, e);
+        let mut table: toml::Table = toml::from_str(&text).map_err(invalid)?;
+        expand_table(&mut table, &|var| std::env::var(var).ok())
+            .map_err(|e| format!("invalid config {}: {}", origin, e))?;
+
+        let present: Vec<&'static str> = Self::KEYS
+            .into_iter()
+            .filter(|key| has_key(&table, key))
+            .collect();
+
+        let mut config: Config = table.try_into().map_err(invalid)?;
+        for key in present {
+            config.sources.insert(key, Source::Config(key.to_string()));
+        }
+        Ok(config)
+    }
+
+    /// Apply values given on the command line or via env vars
+    fn merge_cli(mut self, cli: &Cli, matches: &ArgMatches) -> Config {
+        if let Some(log_level) = &cli.log_level {
+            self.log_level = log_level.clone();
+            self.sources
+                .insert("log_level", cli_source(matches, "log_level"));
+        }
+        if let Some(host) = &cli.host {
+            self.server.host = host.clone();
+            self.sources
+                .insert("server.host", cli_source(matches, "host"));
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+            self.sources
+                .insert("server.port", cli_source(matches, "port"));
+        }
+        if let Some(workers) = cli.workers {
+            self.server.workers = workers;
+            self.sources
+                .insert("server.workers", cli_source(matches, "workers"));
+        }
+        self
+    }
+
+    fn value(&self, key: &str) -> String {
+        match key {
+            "log_level" => self.log_level.clone(),
+            "server.host" => self.server.host.clone(),
+            "server.port" => self.server.port.to_string(),
+            "server.workers" => self.server.workers.to_string(),
+            _ => unreachable!("unknown setting {}", key),
+        }
+    }
+
+    fn source(&self, key: &str) -> Source {
+        self.sources.get(key).cloned().unwrap_or(Source::Default)
+    }
+}
+
+/// Expand `${VAR}` and `${VAR:-default}` in every string value of `table`
+///
+/// Only strings are expanded; `port = "${PORT}"` still has to be a number
+/// after parsing, so keep numeric settings unquoted or use the env var flag.
+fn expand_table(
+    table: &mut toml::Table,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    for (_, value) in table.iter_mut() {
+        expand_value(value, lookup)?;
+    }
+    Ok(())
+}
+
+fn expand_value(
+    value: &mut toml::Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    match value {
+        toml::Value::String(s) => *s = expand_vars(s, lookup)?,
+        toml::Value::Array(items) => {
+            for item in items {
+                expand_value(item, lookup)?;
+            }
+        }
+        toml::Value::Table(table) => expand_table(table, lookup)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replace `${VAR}` with the variable's value, or `${VAR:-default}` with
+/// the default when VAR is unset or empty; a bare `$` is left alone
+fn expand_vars(s: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("unterminated `${{` in \"{}\"", s))?;
+        let expr = &after[..end];
+
+        let (var, default) = match expr.split_once(":-") {
+            Some((var, default)) => (var, Some(default)),
+            None => (expr, None),
+        };
+        // Like the shell, only `:-` treats an empty value as unset
+        let value = lookup(var).filter(|v| default.is_none() || !v.is_empty());
+        match (value, default) {
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(default)) => out.push_str(default),
+            (None, None) => {
+                return Err(format!("${{{}}} is not set and has no default", var));
+            }
+        }
+        rest = &after[end + 1..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Whether a dotted key like `server.port` is set in a parsed TOML table
+fn has_key(table: &toml::Table, key: &str) -> bool {
+    match key.split_once('.') {
+        Some((head, rest)) => table
+            .get(head)
+            .and_then(|value| value.as_table())
+            .is_some_and(|inner| has_key(inner, rest)),
+        None => table.contains_key(key),
+    }
+}
+
+/// Quote `s` for a POSIX shell, leaving plain words untouched
+///
+/// Anything outside a conservative safe set is wrapped in single quotes,
+/// with embedded single quotes written as `'\''`.
+fn shell_quote(s: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// The invocation that reproduces this run, with every setting spelled out
+///
+/// `--no-env` is included so the line means the same thing in a different
+/// environment.
+fn effective_command(cli: &Cli, config: &Config) -> String {
+    let mut args: Vec<String> = vec![
+        "envapp".into(),
+        "--api-key".into(),
+        "***".into(),
+        "--database-url".into(),
+        redact_url_password(&cli.database_url),
+        "--log-level".into(),
+        config.log_level.clone(),
+        "--config".into(),
+        cli.config.display().to_string(),
+        "--workers".into(),
+        config.server.workers.to_string(),
+        "--host".into(),
+        config.server.host.clone(),
+        "--port".into(),
+        config.server.port.to_string(),
+    ];
+    if cli.debug {
+        args.push("--debug".into());
+    }
+    if cli.trace_config_source {
+        args.push("--trace-config-source".into());
+    }
+    args.push("--no-env".into());
+
+    args.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `url` with the password in its userinfo (`user:secret@host`) masked
+fn redact_url_password(url: &str) -> String {
+    let Some(scheme_end) = url.find("://").map(|i| i + 3) else {
+        return url.to_string();
+    };
+    let authority_end = url[scheme_end..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |i| scheme_end + i);
+    let authority = &url[scheme_end..authority_end];
+    let Some(userinfo_end) = authority.rfind('@') else {
+        return url.to_string();
+    };
+    match authority[..userinfo_end].find(':') {
+        Some(colon) => format!(
+            "{}***{}",
+            &url[..scheme_end + colon + 1],
+            &url[scheme_end + userinfo_end..]
+        ),
+        None => url.to_string(),
+    }
+}
+
+/// A `.env` skeleton with one entry per env-backed option
+///
+/// Required variables are left uncommented with an empty value; optional
+/// ones are commented out, filled with their default when there is one.
+/// Options with `hide_env_values` never show a default.
+fn env_template(cmd: &clap::Command) -> String {
+    let mut out = format!(
+        "# Environment for {}\n# Copy to .env and fill in the values.\n",
+        cmd.get_name()
+    );
+    for arg in cmd.get_arguments() {
+        let Some(var) = arg.get_env() else {
+            continue;
+        };
+        let var = var.to_string_lossy();
+        out.push('\n');
+        if let Some(help) = arg.get_help() {
+            out.push_str(&format!("# {}\n", help));
+        }
+        let value = match arg.get_default_values() {
+            [default, ..] if !arg.is_hide_env_values_set() => default.to_string_lossy(),
+            _ => "".into(),
+        };
+        if arg.is_required_set() {
+            out.push_str(&format!("{}={}\n", var, value));
+        } else {
+            out.push_str(&format!("# {}={}\n", var, value));
+        }
+    }
+    out
+}
+
+/// JSON Schema for the config file, from `Config`'s serde attributes and
+/// doc comments
+fn config_schema() -> String {
+    let schema = schemars::schema_for!(Config);
+    serde_json::to_string_pretty(&schema).unwrap_or_default()
+}
+
+/// Tell a command-line value apart from one clap read from the arg's env var
+fn cli_source(matches: &ArgMatches, id: &str) -> Source {
+    if matches.value_source(id) != Some(ValueSource::EnvVariable) {
+        return Source::Cli;
+    }
+    let var = Cli::command()
+        .get_arguments()
+        .find(|arg| arg.get_id() == id)
+        .and_then(|arg| arg.get_env())
+        .map(|var| var.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Source::Env(var)
 }
 
 fn main() {
-    let cli = Cli::parse();
+    // Checked before the real parse: the template is for setting up the
+    // required options, so it can't insist on them
+    if early_flag("print_env_template") {
+        print!("{}", env_template(&Cli::command()));
+        return;
+    }
+    if early_flag("print_config_schema") {
+        println!("{}", config_schema());
+        return;
+    }
+
+    let matches = command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let config = match Config::load(&cli.config) {
+        Ok(config) => config.merge_cli(&cli, &matches),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if cli.echo_command {
+        eprintln!("{}", effective_command(&cli, &config));
+    }
 
     println!("Configuration loaded:");
     println!("  Database URL: {}", cli.database_url);
     println!("  API Key: {}...", &cli.api_key[..4.min(cli.api_key.len())]);
-    println!("  Log level: {}", cli.log_level);
+    println!("  Log level: {}", config.log_level);
     println!("  Config file: {}", cli.config.display());
-    println!("  Workers: {}", cli.workers);
+    println!("  Workers: {}", config.server.workers);
     println!("  Debug mode: {}", cli.debug);
-    println!("  Host: {}", cli.host);
-    println!("  Port: {}", cli.port);
+    println!("  Host: {}", config.server.host);
+    println!("  Port: {}", config.server.port);
+
+    if cli.trace_config_source {
+        println!("Config sources:");
+        for key in Config::KEYS {
+            println!(
+                "  {:<14} = {:<12} ({})",
+                key,
+                config.value(key),
+                config.source(key)
+            );
+        }
+    }
 
     // Initialize logging based on log_level
-    match cli.log_level.to_lowercase().as_str() {
+    match config.log_level.to_lowercase().as_str() {
         "debug" => println!("Log level set to DEBUG"),
         "info" => println!("Log level set to INFO"),
         "warn" => println!("Log level set to WARN"),
         "error" => println!("Log level set to ERROR"),
-        _ => println!("Unknown log level: {}", cli.log_level),
+        _ => println!("Unknown log level: {}", config.log_level),
     }
 
     // Your application logic here
     println!("\nStarting application...");
-    println!("Listening on {}:{}", cli.host, cli.port);
+    println!("Listening on {}:{}", config.server.host, config.server.port);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_url_password_masks_only_the_password() {
+        assert_eq!(
+            redact_url_password("postgres://u:secret@h/db"),
+            "postgres://u:***@h/db"
+        );
+        assert_eq!(
+            redact_url_password("postgres://u@h/db?x=a:b@c"),
+            "postgres://u@h/db?x=a:b@c"
+        );
+        assert_eq!(
+            redact_url_password("postgres://localhost/mydb"),
+            "postgres://localhost/mydb"
+        );
+    }
+
+    #[test]
+    fn expand_vars_treats_empty_as_unset_only_with_a_default() {
+        let lookup = |var: &str| (var == "EMPTY").then(String::new);
+        assert_eq!(expand_vars("${EMPTY}", &lookup).unwrap(), "");
+        assert_eq!(expand_vars("${EMPTY:-info}", &lookup).unwrap(), "info");
+        assert_eq!(expand_vars("${UNSET:-info}", &lookup).unwrap(), "info");
+        assert!(expand_vars("${UNSET}", &lookup).is_err());
+    }
+
+    #[test]
+    fn effective_command_hides_secrets() {
+        let cli = Cli::try_parse_from([
+            "envapp",
+            "--api-key",
+            "sk-1234",
+            "--database-url",
+            "postgres://u:secret@h/db",
+        ])
+        .unwrap();
+        let line = effective_command(&cli, &Config::default());
+        assert!(!line.contains("sk-1234"));
+        assert!(!line.contains("secret"));
+    }
+
+    #[test]
+    fn config_schema_describes_server_port() {
+        let schema: serde_json::Value = serde_json::from_str(&config_schema()).unwrap();
+        let port = &schema["definitions"]["ServerConfig"]["properties"]["port"];
+        assert_eq!(port["type"], "integer");
+        assert_eq!(port["default"], 8080);
+        assert!(schema["properties"].get("sources").is_none());
+    }
 }
 
 // Example usage:
@@ -97,3 +645,37 @@ fn main() {
 // 3. Mix environment and CLI:
 //    export DATABASE_URL="postgres://localhost/mydb"
 //    cargo run -- --api-key "sk-1234" --debug
+//
+// 4. Partial config file (missing keys use the defaults):
+//    # config.toml
+//    log_level = "debug"
+//    [server]
+//    port = 9000
+//    cargo run -- --api-key "sk-1234" --workers 2
+//
+// 5. Boolean env vars accept 1/0, yes/no, on/off, true/false:
+//    DEBUG=yes cargo run -- --api-key "sk-1234"
+//
+// 6. Show where each setting came from:
+//    PORT=9090 cargo run -- --api-key "sk-1234" --workers 2 --trace-config-source
+//
+// 7. Reference env vars from the config file:
+//    # config.toml
+//    [server]
+//    host = "${BIND_HOST:-127.0.0.1}"
+//
+// 8. Pipe a generated config on stdin (env expansion still applies):
+//    generate-config | cargo run -- --api-key "sk-1234" --config -
+//
+// 9. Ignore the environment for a reproducible run:
+//    PORT=9090 cargo run -- --api-key "sk-1234" --database-url postgres://localhost/db --no-env
+//
+// 10. Echo the resolved command line (stderr) for an audit log:
+//    WORKER_COUNT=8 cargo run -- --api-key "sk-1234" --database-url "postgres://me@db/it's" --echo-command
+//    envapp --api-key '***' --database-url 'postgres://me@db/it'\''s' ... --workers 8 ... --no-env
+//
+// 11. Start a .env for a new checkout:
+//    cargo run -- --print-env-template > .env
+//
+// 12. Give editors a schema for config.toml:
+//    cargo run -- --print-config-schema > config.schema.json
@@ -5,9 +5,20 @@
 /// - Nested command structure
 /// - Per-subcommand arguments
 /// - Enum-based command routing
+/// - Subcommand aliases (visible and hidden)
+/// - Bounded parallelism with deterministic output (add --jobs)
+/// - Glob inputs with .gitignore-style exclusions (add --input-glob/--ignore)
+/// - Subcommand path introspection for wrapper scripts (which-command)
+/// - Reusable --limit/--offset pagination for list output
+/// - Failure policy for multi-file commands (add --on-error continue|abort)
+/// - grep-style context around matches (grep --before/--after/--context)
 
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
 
 #[derive(Parser)]
 #[command(name = "git-like")]
@@ -36,17 +47,40 @@ enum Commands {
     },
 
     /// Add files to staging area
+    #[command(alias = "stage")]
     Add {
         /// Files to add
-        #[arg(value_name = "FILE", required = true)]
+        #[arg(value_name = "FILE", required_unless_present = "input_glob")]
         files: Vec<PathBuf>,
 
+        /// Also add files matching a glob (`*`, `?`, and `**` for any depth)
+        #[arg(long, value_name = "PATTERN")]
+        input_glob: Option<String>,
+
+        /// Leave out glob matches for this .gitignore-style pattern (repeatable)
+        #[arg(long, value_name = "PATTERN", requires = "input_glob")]
+        ignore: Vec<String>,
+
         /// Add all files
         #[arg(short = 'A', long)]
         all: bool,
+
+        /// Number of files to stage in parallel
+        #[arg(
+            short,
+            long,
+            default_value_t = 1,
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        jobs: usize,
+
+        /// What to do when a file can't be staged
+        #[arg(long, value_enum, default_value_t = OnError::Abort)]
+        on_error: OnError,
     },
 
     /// Commit staged changes
+    #[command(visible_alias = "ci")]
     Commit {
         /// Commit message
         #[arg(short, long)]
@@ -58,10 +92,42 @@ enum Commands {
     },
 
     /// Remote repository operations
+    #[command(visible_alias = "r")]
     Remote {
         #[command(subcommand)]
         command: RemoteCommands,
     },
+
+    /// Print lines containing PATTERN, with optional surrounding lines
+    #[command(visible_alias = "filter")]
+    Grep {
+        /// Text to search for (plain substring)
+        pattern: String,
+
+        /// Files to search
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// Lines to print before each match
+        #[arg(short = 'B', long, value_name = "N")]
+        before: Option<usize>,
+
+        /// Lines to print after each match
+        #[arg(short = 'A', long, value_name = "N")]
+        after: Option<usize>,
+
+        /// Lines to print before and after each match; --before and
+        /// --after take precedence
+        #[arg(short = 'C', long, value_name = "N", default_value_t = 0)]
+        context: usize,
+    },
+
+    /// Print the subcommand path ARGS resolve to, without running it
+    WhichCommand {
+        /// Arguments as they would follow `git-like`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        args: Vec<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -76,19 +142,296 @@ enum RemoteCommands {
     },
 
     /// Remove a remote
+    #[command(visible_alias = "rm")]
     Remove {
         /// Remote name
         name: String,
     },
 
     /// List all remotes
+    #[command(alias = "ls")]
     List {
         /// Show URLs
         #[arg(short, long)]
         verbose: bool,
+
+        #[command(flatten)]
+        page: PageOpts,
     },
 }
 
+/// Pagination for commands that print collections
+#[derive(Args)]
+struct PageOpts {
+    /// Show at most N items (0 = no limit)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    limit: usize,
+
+    /// Skip the first N items
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    offset: usize,
+}
+
+impl PageOpts {
+    /// The slice of `items` this page covers (empty past the end)
+    fn apply<'a, T>(&self, items: &'a [T]) -> &'a [T] {
+        let rest = items.get(self.offset..).unwrap_or(&[]);
+        match self.limit {
+            0 => rest,
+            limit => &rest[..limit.min(rest.len())],
+        }
+    }
+}
+
+/// Configured remotes as (name, URL) pairs
+fn remotes() -> Vec<(&'static str, &'static str)> {
+    // Replace with the repository's real remote list
+    vec![
+        ("origin", "https://example.com/repo.git"),
+        ("upstream", "https://example.com/upstream/repo.git"),
+        ("fork", "git@example.com:me/repo.git"),
+    ]
+}
+
+/// Resolve `args` to their canonical subcommand path, e.g. `remote add`
+///
+/// Goes through the real parser, so aliases (`r`, `rm`, `stage`) map to
+/// the names they stand for and invalid arguments are rejected.
+fn which_command(args: &[String]) -> Result<String, clap::Error> {
+    let mut cmd = Cli::command();
+    let name = cmd.get_name().to_string();
+    let matches = cmd.try_get_matches_from_mut(std::iter::once(&name).chain(args))?;
+
+    let mut path = Vec::new();
+    let mut current = &matches;
+    while let Some((sub, sub_matches)) = current.subcommand() {
+        path.push(sub);
+        current = sub_matches;
+    }
+    Ok(path.join(" "))
+}
+
+/// Match a `/`-separated path against a glob
+///
+/// `*` and `?` stay within one path segment; a `**` segment spans any
+/// number of segments, including none.
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let segments = |s: &str| -> Vec<Vec<char>> {
+        s.split('/')
+            .filter(|seg| !seg.is_empty() && *seg != ".")
+            .map(|seg| seg.chars().collect())
+            .collect()
+    };
+    match_segments(&segments(pattern), &segments(path))
+}
+
+fn match_segments(pattern: &[Vec<char>], path: &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first[..] == ['*', '*'] => {
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((first, rest)) => path.split_first().is_some_and(|(segment, path_rest)| {
+            match_segment(first, segment) && match_segments(rest, path_rest)
+        }),
+    }
+}
+
+fn match_segment(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some(('*', rest)), _) => {
+            match_segment(rest, name) || (!name.is_empty() && match_segment(pattern, &name[1..]))
+        }
+        (Some(('?', rest)), Some((_, name_rest))) => match_segment(rest, name_rest),
+        (Some((p, rest)), Some((n, name_rest))) => p == n && match_segment(rest, name_rest),
+        _ => false,
+    }
+}
+
+/// Whether `path` matches any of the .gitignore-style `ignores`
+///
+/// A pattern containing `/` is matched against the whole path; one without
+/// matches any single component, so `target` skips everything beneath it.
+fn is_ignored(path: &Path, ignores: &[String]) -> bool {
+    let path = path
+        .to_string_lossy()
+        .replace(std::path::MAIN_SEPARATOR, "/");
+    ignores.iter().any(|pattern| {
+        let pattern = pattern.trim_end_matches('/');
+        if pattern.contains('/') {
+            glob_match(pattern, &path)
+        } else {
+            path.split('/').any(|segment| glob_match(pattern, segment))
+        }
+    })
+}
+
+/// Resolve `pattern` to the sorted files it matches, minus ignored ones
+fn resolve_glob(pattern: &str, ignores: &[String]) -> io::Result<Vec<PathBuf>> {
+    // Walk only from the part of the pattern before the first wildcard
+    let base: Vec<&str> = pattern
+        .split('/')
+        .take_while(|segment| !segment.contains(['*', '?']))
+        .collect();
+    let base = match base.join("/") {
+        root if root.is_empty() && pattern.starts_with('/') => PathBuf::from("/"),
+        root if root.is_empty() => PathBuf::from("."),
+        root => PathBuf::from(root),
+    };
+
+    let mut found = BTreeSet::new();
+    if base.is_file() {
+        found.insert(base);
+    } else if base.is_dir() {
+        walk(&base, ignores, &mut found)?;
+    }
+
+    Ok(found
+        .into_iter()
+        .map(|path| {
+            path.strip_prefix(".")
+                .map(Path::to_path_buf)
+                .unwrap_or(path)
+        })
+        .filter(|path| {
+            let path = path
+                .to_string_lossy()
+                .replace(std::path::MAIN_SEPARATOR, "/");
+            glob_match(pattern, &path)
+        })
+        .filter(|path| !is_ignored(path, ignores))
+        .collect())
+}
+
+/// Collect regular files under `dir`, pruning ignored directories
+///
+/// Symlinked directories are not followed, so link cycles can't loop.
+fn walk(dir: &Path, ignores: &[String], found: &mut BTreeSet<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let relative = path.strip_prefix(".").unwrap_or(&path);
+        if is_ignored(relative, ignores) {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            walk(&path, ignores, found)?;
+        } else {
+            found.insert(path);
+        }
+    }
+    Ok(())
+}
+
+/// One line of `grep` output
+#[derive(Debug, PartialEq, Eq)]
+enum GrepLine<'a> {
+    /// A line containing the pattern, with its 1-based number
+    Match(usize, &'a str),
+    /// A line within --before/--after of a match
+    Context(usize, &'a str),
+    /// `--` between groups that aren't adjacent
+    Separator,
+}
+
+/// Matching lines of `text` plus `before`/`after` lines of context
+///
+/// Overlapping or touching groups merge into one, so each line appears at
+/// most once and separators only fall where lines were skipped.
+fn grep_lines<'a>(text: &'a str, pattern: &str, before: usize, after: usize) -> Vec<GrepLine<'a>> {
+    let lines: Vec<&str> = text.lines().collect();
+    let matched: Vec<bool> = lines.iter().map(|line| line.contains(pattern)).collect();
+    let mut shown = vec![false; lines.len()];
+    for index in (0..lines.len()).filter(|&i| matched[i]) {
+        let end = (index + after).min(lines.len() - 1);
+        shown[index.saturating_sub(before)..=end].fill(true);
+    }
+
+    let mut out = Vec::new();
+    let mut last = None;
+    for (index, line) in lines.iter().enumerate().filter(|&(i, _)| shown[i]) {
+        if last.is_some_and(|last| last + 1 < index) {
+            out.push(GrepLine::Separator);
+        }
+        out.push(if matched[index] {
+            GrepLine::Match(index + 1, line)
+        } else {
+            GrepLine::Context(index + 1, line)
+        });
+        last = Some(index);
+    }
+    out
+}
+
+/// Stage a single file (placeholder: checks it is a readable regular file)
+fn stage_file(path: &Path) -> Result<u64, String> {
+    let metadata = std::fs::metadata(path).map_err(|e| e.to_string())?;
+    if metadata.is_file() {
+        Ok(metadata.len())
+    } else {
+        Err("not a regular file".to_string())
+    }
+}
+
+/// How a multi-file command handles a file that fails
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OnError {
+    /// Keep going and list every failure at the end
+    Continue,
+    /// Stop starting new files after the first failure (default)
+    Abort,
+}
+
+/// Stage `files` on at most `jobs` threads
+///
+/// Results come back in input order regardless of which thread finished
+/// first, so output stays deterministic. Under `OnError::Abort`, files not
+/// started before the first failure come back as `None`.
+fn stage_files(
+    files: &[PathBuf],
+    jobs: usize,
+    on_error: OnError,
+) -> Vec<Option<Result<u64, String>>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let workers = jobs.clamp(1, files.len().max(1));
+
+    let mut results: Vec<(usize, Result<u64, String>)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    while !(on_error == OnError::Abort && failed.load(Ordering::Relaxed)) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = files.get(index) else {
+                            break;
+                        };
+                        let result = stage_file(file);
+                        if result.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        done.push((index, result));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("staging worker panicked"))
+            .collect()
+    });
+
+    results.sort_by_key(|(index, _)| *index);
+    let mut staged: Vec<Option<Result<u64, String>>> = files.iter().map(|_| None).collect();
+    for (index, result) in results {
+        staged[index] = Some(result);
+    }
+    staged
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -104,16 +447,61 @@ fn main() {
             );
         }
 
-        Commands::Add { files, all } => {
+        Commands::Add {
+            files,
+            all,
+            jobs,
+            input_glob,
+            ignore,
+            on_error,
+        } => {
+            let mut files = files.clone();
+            if let Some(pattern) = input_glob {
+                match resolve_glob(pattern, ignore) {
+                    Ok(matched) if matched.is_empty() => {
+                        eprintln!("error: --input-glob '{}' matched no files", pattern);
+                        std::process::exit(1);
+                    }
+                    Ok(matched) => files.extend(matched),
+                    Err(e) => {
+                        eprintln!("error: failed to expand '{}': {}", pattern, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+
             if *all {
                 println!("Adding all files");
             } else {
                 println!("Adding {} file(s)", files.len());
-                if cli.verbose {
-                    for file in files {
-                        println!("  - {}", file.display());
+                let results = stage_files(&files, *jobs, *on_error);
+                let mut failures = Vec::new();
+                let mut skipped = 0;
+                for (file, result) in files.iter().zip(&results) {
+                    match result {
+                        Some(Ok(_)) if cli.verbose => println!("  - {}", file.display()),
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => failures.push((file, e)),
+                        None => skipped += 1,
                     }
                 }
+                let staged = files.len() - failures.len() - skipped;
+                if skipped > 0 {
+                    println!(
+                        "Staged {} file(s), {} failed, {} skipped (use --on-error continue to stage the rest)",
+                        staged,
+                        failures.len(),
+                        skipped
+                    );
+                } else {
+                    println!("Staged {} file(s), {} failed", staged, failures.len());
+                }
+                for (file, e) in &failures {
+                    eprintln!("  ✗ {}: {}", file.display(), e);
+                }
+                if !failures.is_empty() {
+                    std::process::exit(1);
+                }
             }
         }
 
@@ -131,9 +519,85 @@ fn main() {
             RemoteCommands::Remove { name } => {
                 println!("Removing remote '{}'", name);
             }
-            RemoteCommands::List { verbose } => {
+            RemoteCommands::List { verbose, page } => {
                 println!("Listing remotes{}", if *verbose { " (verbose)" } else { "" });
+                for (name, url) in page.apply(&remotes()) {
+                    if *verbose {
+                        println!("{}\t{}", name, url);
+                    } else {
+                        println!("{}", name);
+                    }
+                }
+            }
+        },
+
+        Commands::Grep {
+            pattern,
+            files,
+            before,
+            after,
+            context,
+        } => {
+            let before = before.unwrap_or(*context);
+            let after = after.unwrap_or(*context);
+            let mut found = false;
+            let mut failed = false;
+            for file in files {
+                let text = match std::fs::read_to_string(file) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        eprintln!("error: {}: {}", file.display(), e);
+                        failed = true;
+                        continue;
+                    }
+                };
+                // Like grep, prefix with the file name when searching several
+                let name = if files.len() > 1 {
+                    format!("{}:", file.display())
+                } else {
+                    String::new()
+                };
+                let lines = grep_lines(&text, pattern, before, after);
+                if found && !lines.is_empty() && before + after > 0 {
+                    println!("--");
+                }
+                for line in lines {
+                    match line {
+                        GrepLine::Match(number, line) => {
+                            found = true;
+                            println!("{}{}:{}", name, number, line);
+                        }
+                        GrepLine::Context(number, line) => println!("{}{}-{}", name, number, line),
+                        GrepLine::Separator => println!("--"),
+                    }
+                }
+            }
+            // grep's convention: 0 found, 1 no match, 2 error
+            if failed {
+                std::process::exit(2);
+            } else if !found {
+                std::process::exit(1);
             }
+        }
+
+        Commands::WhichCommand { args } => match which_command(args) {
+            Ok(path) => println!("{}", path),
+            Err(e) => e.exit(),
         },
     }
 }
+
+// Example usage:
+//
+// git-like commit -m "Initial commit"
+// git-like ci -m "Same as commit"      (visible alias, shown in --help)
+// git-like stage src/main.rs           (hidden alias for add)
+// git-like add --jobs 4 src/*.rs
+// git-like add --input-glob 'src/**/*.rs' --ignore target --ignore '*_test.rs'
+// git-like add a.rs missing.rs c.rs --on-error continue   (stages a.rs and c.rs, exits 1)
+// git-like r add origin https://example.com/repo.git
+// git-like r ls --verbose
+// git-like remote list --offset 1 --limit 1
+// git-like grep TODO -C 2 src/main.rs   (two lines around each match, `--` between groups)
+// git-like filter error -B 1 -A 3 app.log   (visible alias for grep)
+// git-like which-command r add origin https://example.com/repo.git   (prints "remote add")
@@ -5,11 +5,56 @@
 /// - Type-safe option selection
 /// - Automatic validation and help text
 /// - Pattern matching on enums
+/// - Reusable argument groups (flatten)
+/// - `-` as stdin/stdout for file arguments
+/// - Input format detection from the extension, then the content, with an
+///   explicit override (--input-format) and a last resort (--assume-format)
+/// - Output cache keyed by input content (--cache-dir)
+/// - Timestamped status log (--log-file, --time-format)
+/// - Size-based log rotation (--log-max-size 10MiB --log-keep 3)
+/// - Warnings (or errors with --strict) for flags --format ignores
+/// - Partial input by byte range (--range 0-99, --range 1000-)
+/// - Binary protobuf output from a FileDescriptorSet (--format protobuf)
+/// - Duplicate removal for arrays of records (--dedupe, --dedupe=KEY)
+/// - Permissions for output files (--output-mode 600)
+/// - Tunable output buffering with byte sizes (--output-buffer-size 1MiB)
+/// - Stable record ordering by a field (--sort-by, --reverse)
+/// - Field selection with dotted paths (--fields name,server.port)
+/// - One output file per record (--output-split DIR --split-template
+///   '{id}.{ext}')
+/// - JSON Schema validation of the input before writing (--schema)
+/// - One YAML document per record (--yaml-multi-doc)
+/// - Byte-identical output for equal data (--canonicalize)
+/// - Object keys in input order instead of sorted (--preserve-order)
+/// - Input/output size report (--measure)
+/// - `query` subcommand printing the value at a JSON Pointer (/server/port)
+/// - Opposing layout flags (--pretty / --compact) that conflict with each other
+/// - Memory cap for hostile or oversized input (--limit-memory 256MiB)
+/// - UTF-16LE and Latin-1 input transcoded to UTF-8 (--input-encoding)
+/// - Whitespace around CSV input cells stripped on request (--trim-fields)
+///
+/// Conversion goes through `serde_json::Value`, so add `serde_json` (with
+/// its `preserve_order` feature), `serde_yaml`, `toml` and `csv` to
+/// Cargo.toml. Protobuf output also needs
+/// `prost` and `prost-reflect` (with its `serde` feature). --schema uses
+/// `jsonschema = "0.30"`; later releases changed how an error's instance
+/// path is read.
 
-use clap::{Parser, ValueEnum};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Output format options
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum Format {
     /// JavaScript Object Notation
     Json,
@@ -19,10 +64,72 @@ enum Format {
     Toml,
     /// Comma-Separated Values
     Csv,
+    /// Protocol Buffers binary (output only; needs --proto-descriptor)
+    Protobuf,
+}
+
+impl Format {
+    /// File extension for output in this format
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Toml => "toml",
+            Format::Csv => "csv",
+            Format::Protobuf => "pb",
+        }
+    }
+
+    /// Guess the format from a file extension
+    fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from the start of the content
+    ///
+    /// Only recognizes unambiguous openings: JSON that parses, a YAML
+    /// `---`/`%YAML` header, or a TOML `[table]` or `key = value` line.
+    /// CSV and bare YAML mappings return `None`.
+    fn sniff(data: &str) -> Option<Format> {
+        let text = data.trim_start_matches('\u{feff}').trim_start();
+        if text.starts_with(['{', '[']) && serde_json::from_str::<Value>(text).is_ok() {
+            return Some(Format::Json);
+        }
+        if text.starts_with("---") || text.starts_with("%YAML") {
+            return Some(Format::Yaml);
+        }
+
+        let line = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))?;
+        let is_key = |key: &str| {
+            let key = key.trim();
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-.\"".contains(c))
+        };
+        let table = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+            .map(|name| name.trim_matches(['[', ']']));
+        match (table, line.split_once('=')) {
+            (Some(name), _) if is_key(name) => Some(Format::Toml),
+            (None, Some((key, _))) if is_key(key) => Some(Format::Toml),
+            _ => None,
+        }
+    }
 }
 
 /// Log level options
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum LogLevel {
     /// Detailed debug information
     Debug,
@@ -34,27 +141,633 @@ enum LogLevel {
     Error,
 }
 
+/// Timestamp prefix for log lines
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum TimeFormat {
+    /// No timestamp
+    None,
+    /// UTC date and time, e.g. 2024-05-01T12:00:00.000Z
+    Rfc3339,
+    /// Seconds since the Unix epoch
+    Epoch,
+    /// Seconds since the program started
+    Relative,
+}
+
+impl TimeFormat {
+    /// Format the current time, or `None` for `TimeFormat::None`
+    fn stamp(self, start: Instant) -> Option<String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        match self {
+            TimeFormat::None => None,
+            TimeFormat::Rfc3339 => Some(rfc3339(now)),
+            TimeFormat::Epoch => Some(format!("{}.{:03}", now.as_secs(), now.subsec_millis())),
+            TimeFormat::Relative => Some(format!("+{:.3}s", start.elapsed().as_secs_f64())),
+        }
+    }
+}
+
+/// Format a time since the Unix epoch as an RFC 3339 UTC timestamp
+fn rfc3339(since_epoch: Duration) -> String {
+    let secs = since_epoch.as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days (Howard Hinnant), shifted so years start in March
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Status log on stderr or `--log-file`, one timestamped line per message
+struct Logger {
+    sink: Box<dyn Write>,
+    time_format: TimeFormat,
+    start: Instant,
+}
+
+impl Logger {
+    /// Timestamps default to RFC 3339 for log files and piped stderr, and
+    /// to none on a terminal where a person is watching
+    fn new(cli: &Cli) -> io::Result<Logger> {
+        let (sink, default_format): (Box<dyn Write>, _) = match &cli.log_file {
+            Some(path) => match cli.log_max_size {
+                Some(max_size) => {
+                    let file = RotatingFile::open(path.clone(), max_size, cli.log_keep)?;
+                    (Box::new(file), TimeFormat::Rfc3339)
+                }
+                None => {
+                    let file = OpenOptions::new().create(true).append(true).open(path)?;
+                    (Box::new(file), TimeFormat::Rfc3339)
+                }
+            },
+            None if atty::is(atty::Stream::Stderr) => (Box::new(io::stderr()), TimeFormat::None),
+            None => (Box::new(io::stderr()), TimeFormat::Rfc3339),
+        };
+        Ok(Logger {
+            sink,
+            time_format: cli.time_format.unwrap_or(default_format),
+            start: Instant::now(),
+        })
+    }
+
+    fn log(&mut self, message: impl fmt::Display) {
+        // One write per line, so rotation never splits a message
+        let line = match self.time_format.stamp(self.start) {
+            Some(stamp) => format!("{} {}\n", stamp, message),
+            None => format!("{}\n", message),
+        };
+        // A failed log write shouldn't abort the conversion
+        let _ = self.sink.write_all(line.as_bytes());
+    }
+}
+
+/// Log file that moves itself aside once it would grow past `max_size`
+///
+/// The current file is renamed to `PATH.1` and older backups shift up by
+/// one, so only the newest `keep` survive.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn backup(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            // Renaming onto PATH.<keep> drops the oldest backup
+            for n in (1..self.keep).rev() {
+                let from = self.backup(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.backup(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.backup(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
 /// Color output mode
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum ColorMode {
-    /// Always use colors
+    /// Always use colors, even when piped or NO_COLOR is set
     Always,
     /// Never use colors
     Never,
-    /// Automatically detect (default)
+    /// Use colors on a terminal unless NO_COLOR is set (default)
     Auto,
 }
 
+impl ColorMode {
+    /// Decide whether to emit color
+    ///
+    /// Precedence: an explicit `Always` beats `NO_COLOR`, which beats
+    /// terminal detection. CI logs render ANSI but aren't TTYs, so they need
+    /// `Always` (or `--force-color`).
+    fn enabled(self, no_color: bool, is_tty: bool) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => !no_color && is_tty,
+        }
+    }
+}
+
+/// A file argument where `-` stands for stdin (inputs) or stdout (outputs)
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FileOrStdio {
+    Stdio,
+    Path(PathBuf),
+}
+
+impl FileOrStdio {
+    /// Open for reading: stdin for `-`, otherwise the file
+    fn open(&self) -> io::Result<Box<dyn BufRead>> {
+        match self {
+            FileOrStdio::Stdio => Ok(Box::new(BufReader::new(io::stdin()))),
+            FileOrStdio::Path(path) => Ok(Box::new(BufReader::new(File::open(path)?))),
+        }
+    }
+
+    /// Open for writing: stdout for `-`, otherwise create the file and
+    /// either truncate it or append to it (`append` is ignored for stdout).
+    /// On Unix the file is set to permission bits `mode`, whether new or
+    /// not; without one, a new file gets DEFAULT_OUTPUT_MODE and an existing
+    /// file keeps its own. Writes are buffered in chunks of `buffer_size`
+    /// bytes.
+    fn create(
+        &self,
+        append: bool,
+        mode: Option<u32>,
+        buffer_size: usize,
+    ) -> io::Result<Box<dyn Write>> {
+        match self {
+            FileOrStdio::Stdio => Ok(Box::new(BufWriter::with_capacity(
+                buffer_size,
+                io::stdout(),
+            ))),
+            FileOrStdio::Path(path) => {
+                let mut options = OpenOptions::new();
+                options
+                    .write(true)
+                    .create(true)
+                    .append(append)
+                    .truncate(!append);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(
+                    &mut options,
+                    mode.unwrap_or(DEFAULT_OUTPUT_MODE),
+                );
+                let file = options.open(path)?;
+                // The mode above only applies when the file is created
+                #[cfg(unix)]
+                if let Some(mode) = mode {
+                    use std::os::unix::fs::PermissionsExt;
+                    file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+                }
+                // main warns that --output-mode does nothing here
+                #[cfg(not(unix))]
+                let _ = mode;
+                Ok(Box::new(BufWriter::with_capacity(buffer_size, file)))
+            }
+        }
+    }
+}
+
+impl FileOrStdio {
+    fn path(&self) -> Option<&Path> {
+        match self {
+            FileOrStdio::Stdio => None,
+            FileOrStdio::Path(path) => Some(path),
+        }
+    }
+}
+
+impl fmt::Display for FileOrStdio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileOrStdio::Stdio => write!(f, "-"),
+            FileOrStdio::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Parse a file argument, mapping `-` to the standard stream
+fn parse_file_or_stdio(s: &str) -> Result<FileOrStdio, String> {
+    match s {
+        "" => Err("path cannot be empty".to_string()),
+        "-" => Ok(FileOrStdio::Stdio),
+        path => Ok(FileOrStdio::Path(PathBuf::from(path))),
+    }
+}
+
+/// Parse a size like `4096`, `64KiB`, `1MB` or `2GiB`; KB/MB/GB are powers
+/// of 1000 and KiB/MiB/GiB powers of 1024
+fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let n: u64 = number
+        .parse()
+        .map_err(|_| format!("`{}` is not a size (e.g. 4096, 64KiB, 1MB)", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "kib" => 1 << 10,
+        "mb" => 1000 * 1000,
+        "mib" => 1 << 20,
+        "gb" => 1000 * 1000 * 1000,
+        "gib" => 1 << 30,
+        _ => {
+            return Err(format!(
+                "unknown unit `{}` in `{}`; use B, KB, KiB, MB, MiB, GB or GiB",
+                unit, s
+            ))
+        }
+    };
+    n.checked_mul(multiplier)
+        .ok_or_else(|| format!("`{}` is too large", s))
+}
+
+/// Smallest accepted --output-buffer-size; below this writes get chatty
+const MIN_OUTPUT_BUFFER: usize = 1 << 10;
+
+/// Permission bits for output files created without --output-mode
+const DEFAULT_OUTPUT_MODE: u32 = 0o644;
+
+/// Parse Unix permission bits written in octal (`600`, `0640`, `0o600`)
+fn parse_octal_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("`{}` is not an octal mode like 600 or 644", s)),
+    }
+}
+
+/// Parse --output-buffer-size, requiring at least MIN_OUTPUT_BUFFER bytes
+fn parse_buffer_size(s: &str) -> Result<usize, String> {
+    let size = usize::try_from(parse_byte_size(s)?).map_err(|_| format!("`{}` is too large", s))?;
+    if size < MIN_OUTPUT_BUFFER {
+        return Err(format!(
+            "buffer must be at least {} bytes (1KiB)",
+            MIN_OUTPUT_BUFFER
+        ));
+    }
+    Ok(size)
+}
+
+/// Common I/O options, flattened into the parser with `#[command(flatten)]`
+#[derive(Args)]
+struct IoOpts {
+    /// Input file (`-` for stdin)
+    #[arg(
+        required = true,
+        value_parser = parse_file_or_stdio,
+        value_hint = ValueHint::FilePath
+    )]
+    input: Option<FileOrStdio>,
+
+    /// Output file (`-` or omitted for stdout)
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        value_parser = parse_file_or_stdio,
+        value_hint = ValueHint::FilePath
+    )]
+    output: Option<FileOrStdio>,
+
+    /// Append to the output file instead of overwriting it
+    #[arg(long)]
+    append: bool,
+
+    /// Buffer size for writing the output (e.g. 64KiB, 1MiB)
+    #[arg(long, value_name = "BYTES", value_parser = parse_buffer_size, default_value = "64KiB")]
+    output_buffer_size: usize,
+
+    /// Permissions for the output file, in octal (e.g. 600 for secrets);
+    /// also applied to an existing file [default: 644 for a new file]; Unix
+    /// only
+    #[arg(long, value_name = "OCTAL", value_parser = parse_octal_mode)]
+    output_mode: Option<u32>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+
+    /// Input format, overriding detection from the file extension or content
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    input_format: Option<Format>,
+
+    /// Input format to use when neither the extension nor the content
+    /// gives it away (e.g. CSV on stdin)
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        conflicts_with = "input_format"
+    )]
+    assume_format: Option<Format>,
+
+    /// Only read bytes START through END of the input (inclusive); leave
+    /// END off to read to the end, e.g. `1000-`
+    #[arg(long, value_name = "START-END", value_parser = parse_range)]
+    range: Option<(u64, Option<u64>)>,
+
+    /// Character encoding of the input; utf8 also accepts a UTF-16LE file
+    /// that starts with a byte order mark
+    #[arg(long, value_enum, value_name = "ENCODING", default_value_t = InputEncoding::Utf8)]
+    input_encoding: InputEncoding,
+
+    /// Strip leading and trailing whitespace from each cell of CSV input
+    /// (` a , b ` reads as `a` and `b`)
+    #[arg(long)]
+    trim_fields: bool,
+
+    /// Fail instead of reading more than SIZE bytes of input or producing
+    /// more than SIZE bytes of output (e.g. 256MiB)
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size)]
+    limit_memory: Option<u64>,
+}
+
+/// Input character encodings, transcoded to UTF-8 before parsing
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum InputEncoding {
+    /// UTF-8, with an optional byte order mark
+    Utf8,
+    /// UTF-16 little-endian, as written by many Windows tools
+    Utf16le,
+    /// ISO-8859-1; every byte is one character
+    Latin1,
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+
+impl InputEncoding {
+    /// Decode `bytes` to a string, failing on sequences that aren't valid in
+    /// this encoding
+    fn decode(self, bytes: &[u8]) -> Result<String, String> {
+        match self {
+            InputEncoding::Utf8 if bytes.starts_with(UTF16LE_BOM) => {
+                InputEncoding::Utf16le.decode(bytes)
+            }
+            InputEncoding::Utf8 => {
+                let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec()).map_err(|e| {
+                    format!(
+                        "invalid UTF-8 at byte {}; try --input-encoding",
+                        e.utf8_error().valid_up_to()
+                    )
+                })
+            }
+            InputEncoding::Utf16le => {
+                let bytes = bytes.strip_prefix(UTF16LE_BOM).unwrap_or(bytes);
+                if !bytes.len().is_multiple_of(2) {
+                    return Err("UTF-16 input has an odd number of bytes".to_string());
+                }
+                let units = bytes
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+                char::decode_utf16(units)
+                    .collect::<Result<String, _>>()
+                    .map_err(|e| format!("invalid UTF-16: {}", e))
+            }
+            InputEncoding::Latin1 => Ok(bytes.iter().map(|&b| char::from(b)).collect()),
+        }
+    }
+}
+
+/// Parse an inclusive byte range `START-END` or open-ended `START-`
+fn parse_range(s: &str) -> Result<(u64, Option<u64>), String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("`{}` is not a range; expected START-END or START-", s))?;
+    let start: u64 = start
+        .parse()
+        .map_err(|_| format!("`{}` is not a valid start offset", start))?;
+    if end.is_empty() {
+        return Ok((start, None));
+    }
+    let end: u64 = end
+        .parse()
+        .map_err(|_| format!("`{}` is not a valid end offset", end))?;
+    if start > end {
+        return Err(format!("range start {} is after its end {}", start, end));
+    }
+    Ok((start, Some(end)))
+}
+
+/// Read `input` as text in `--input-encoding`, limited to `--range` and
+/// `--limit-memory` when given; also returns how many raw bytes were read
+///
+/// The range counts bytes of the raw input, before decoding.
+fn read_input(input: &FileOrStdio, opts: &IoOpts) -> io::Result<(String, u64)> {
+    let reader: Box<dyn Read> = match (opts.range, input) {
+        (None, input) => input.open()?,
+        (Some((start, end)), input) => {
+            let reader: Box<dyn Read> = match input {
+                FileOrStdio::Path(path) => {
+                    let mut file = File::open(path)?;
+                    file.seek(SeekFrom::Start(start))?;
+                    Box::new(file)
+                }
+                // stdin can't seek, so read past the skipped bytes
+                FileOrStdio::Stdio => {
+                    let mut stdin = input.open()?;
+                    io::copy(&mut stdin.by_ref().take(start), &mut io::sink())?;
+                    Box::new(stdin)
+                }
+            };
+            let len = end.map_or(u64::MAX, |end| end - start + 1);
+            Box::new(reader.take(len))
+        }
+    };
+
+    let mut reader = LimitedReader::new(reader, opts.limit_memory.unwrap_or(u64::MAX));
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok((decode_input(&data, opts.input_encoding)?, reader.read))
+}
+
+/// Reader that fails once more than `limit` bytes have passed through it,
+/// so an oversized input stops before it is all in memory
+struct LimitedReader<R> {
+    inner: R,
+    limit: u64,
+    /// Bytes read so far
+    read: u64,
+}
+
+impl<R: Read> LimitedReader<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        if self.read > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                format!("input is larger than --limit-memory ({} bytes)", self.limit),
+            ));
+        }
+        Ok(n)
+    }
+}
+
+fn decode_input(data: &[u8], encoding: InputEncoding) -> io::Result<String> {
+    encoding
+        .decode(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Options that only affect `--format protobuf`
+#[derive(Args)]
+struct ProtoOpts {
+    /// Compiled schema: a FileDescriptorSet from `protoc --descriptor_set_out`
+    #[arg(
+        long,
+        value_name = "FILE",
+        value_hint = ValueHint::FilePath,
+        required_if_eq("format", "protobuf")
+    )]
+    proto_descriptor: Option<PathBuf>,
+
+    /// Fully qualified message to encode each record as (e.g. shop.Order)
+    #[arg(long, value_name = "NAME", required_if_eq("format", "protobuf"))]
+    proto_message: Option<String>,
+}
+
+/// Options that only affect `--format csv`
+#[derive(Args)]
+struct CsvOpts {
+    /// Field delimiter for CSV output [default: ,]
+    #[arg(long, value_name = "CHAR", value_parser = parse_delimiter)]
+    csv_delimiter: Option<u8>,
+
+    /// Leave out the CSV header row
+    #[arg(long)]
+    no_headers: bool,
+}
+
+/// Parse a single ASCII delimiter character
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [byte] if byte.is_ascii() => Ok(*byte),
+        _ => Err(format!("`{}` is not a single ASCII character", s)),
+    }
+}
+
+/// Format-specific flags that were given but have no effect on `--format`
+fn ignored_flags(cli: &Cli) -> Vec<&'static str> {
+    let format = cli.io.format;
+    let mut ignored = Vec::new();
+    if cli.pretty && !matches!(format, Format::Json | Format::Toml) {
+        ignored.push("--pretty");
+    }
+    if cli.compact && !matches!(format, Format::Json | Format::Yaml) {
+        ignored.push("--compact");
+    }
+    if cli.yaml_multi_doc && format != Format::Yaml {
+        ignored.push("--yaml-multi-doc");
+    }
+    if format != Format::Protobuf {
+        if cli.proto.proto_descriptor.is_some() {
+            ignored.push("--proto-descriptor");
+        }
+        if cli.proto.proto_message.is_some() {
+            ignored.push("--proto-message");
+        }
+    }
+    if format != Format::Csv {
+        if cli.csv.csv_delimiter.is_some() {
+            ignored.push("--csv-delimiter");
+        }
+        if cli.csv.no_headers {
+            ignored.push("--no-headers");
+        }
+    }
+    ignored
+}
+
 #[derive(Parser)]
 #[command(name = "converter")]
 #[command(about = "Convert data between formats with type-safe options")]
+#[command(subcommand_negates_reqs = true)]
 struct Cli {
-    /// Input file
-    input: std::path::PathBuf,
+    #[command(flatten)]
+    io: IoOpts,
 
-    /// Output format
-    #[arg(short, long, value_enum, default_value_t = Format::Json)]
-    format: Format,
+    #[command(subcommand)]
+    command: Option<Command>,
 
     /// Log level
     #[arg(short, long, value_enum, default_value_t = LogLevel::Info)]
@@ -64,69 +777,933 @@ struct Cli {
     #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
     color: ColorMode,
 
+    /// Force color even when piped (same as --color always)
+    #[arg(long, conflicts_with = "color")]
+    force_color: bool,
+
     /// Pretty print output (for supported formats)
     #[arg(short, long)]
     pretty: bool,
+
+    /// Write JSON or YAML on a single line
+    #[arg(long, conflicts_with = "pretty")]
+    compact: bool,
+
+    /// Print the input and output sizes in bytes, and their ratio, to stderr
+    #[arg(long)]
+    measure: bool,
+
+    /// Sort object keys and write whole-number floats as integers, so
+    /// equal data always produces the same bytes
+    #[arg(long)]
+    canonicalize: bool,
+
+    /// Keep object keys in the order the input has them, instead of
+    /// sorting them
+    #[arg(long, conflicts_with = "canonicalize")]
+    preserve_order: bool,
+
+    /// With --format yaml, write each element of an array as its own
+    /// `---` document instead of one sequence
+    #[arg(long)]
+    yaml_multi_doc: bool,
+
+    #[command(flatten)]
+    csv: CsvOpts,
+
+    #[command(flatten)]
+    proto: ProtoOpts,
+
+    /// Write binary output even when stdout is a terminal, and let
+    /// --output-split records overwrite existing files and earlier records
+    /// with the same name
+    #[arg(long)]
+    force: bool,
+
+    /// Write each record of an array to its own file in DIR (created if
+    /// missing) instead of to --output
+    #[arg(
+        long,
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        conflicts_with_all = ["output", "append"]
+    )]
+    output_split: Option<PathBuf>,
+
+    /// File name for each --output-split record: `{FIELD}` is the
+    /// record's value at a dotted path, `{ext}` the --format extension
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{id}.{ext}",
+        requires = "output_split"
+    )]
+    split_template: String,
+
+    /// Drop repeated array elements, keeping the first; with =KEY, elements
+    /// count as repeats when their KEY field matches
+    #[arg(
+        long,
+        value_name = "KEY",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    dedupe: Option<String>,
+
+    /// Order the records of an array by this field (a dotted path);
+    /// records that compare equal keep their input order
+    #[arg(long, value_name = "FIELD")]
+    sort_by: Option<String>,
+
+    /// Sort in descending order
+    #[arg(long, requires = "sort_by")]
+    reverse: bool,
+
+    /// Only output these fields of each record; dotted paths reach into
+    /// nested objects (e.g. `name,server.port`)
+    #[arg(long, value_name = "PATHS", value_delimiter = ',')]
+    fields: Vec<String>,
+
+    /// Fail instead of warning when a flag doesn't apply to --format or a
+    /// --fields path doesn't exist
+    #[arg(long)]
+    strict: bool,
+
+    /// Check the input against this JSON Schema (JSON or YAML) before
+    /// writing anything; every violation is reported with its path
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    schema: Option<PathBuf>,
+
+    /// Reuse earlier output for unchanged input, stored in this directory
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    cache_dir: Option<PathBuf>,
+
+    /// Append status messages to this file instead of stderr
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    log_file: Option<PathBuf>,
+
+    /// Rotate the log file before it grows past SIZE (e.g. 10MiB)
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size, requires = "log_file")]
+    log_max_size: Option<u64>,
+
+    /// Number of rotated log files (PATH.1, PATH.2, ...) to keep
+    #[arg(long, value_name = "N", default_value_t = 5, requires = "log_max_size")]
+    log_keep: usize,
+
+    /// Timestamp prefix for status messages [default: rfc3339 for a log
+    /// file or piped stderr, none on a terminal]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    time_format: Option<TimeFormat>,
+}
+
+/// Alternatives to converting the whole input
+#[derive(Subcommand)]
+enum Command {
+    /// Print the value at an RFC 6901 JSON Pointer, in --format
+    Query {
+        /// Pointer into the document (e.g. /server/port, /items/0)
+        pointer: String,
+
+        /// File to read, in any input format (`-` for stdin)
+        #[arg(value_parser = parse_file_or_stdio, value_hint = ValueHint::FilePath)]
+        file: FileOrStdio,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    // Configure logging based on log level
+    let format_name = cli
+        .io
+        .format
+        .to_possible_value()
+        .map_or_else(String::new, |v| v.get_name().to_string());
+    for flag in ignored_flags(&cli) {
+        let message = format!("{} has no effect with --format {}", flag, format_name);
+        if cli.strict {
+            Cli::command()
+                .error(ErrorKind::ArgumentConflict, message)
+                .exit();
+        }
+        eprintln!("warning: {}", message);
+    }
+    if cfg!(not(unix)) && cli.io.output_mode.is_some() {
+        eprintln!("warning: --output-mode has no effect on this platform");
+    }
+
+    // Status messages go to stderr so stdout stays clean for `--output -`
+    let mut log = Logger::new(&cli).unwrap_or_else(|e| {
+        eprintln!("Error: failed to open log file: {}", e);
+        std::process::exit(1);
+    });
     match cli.log_level {
-        LogLevel::Debug => println!("🔍 Debug logging enabled"),
-        LogLevel::Info => println!("ℹ️  Info logging enabled"),
-        LogLevel::Warn => println!("⚠️  Warning logging enabled"),
-        LogLevel::Error => println!("❌ Error logging only"),
+        LogLevel::Debug => log.log("🔍 Debug logging enabled"),
+        LogLevel::Info => log.log("ℹ️  Info logging enabled"),
+        LogLevel::Warn => log.log("⚠️  Warning logging enabled"),
+        LogLevel::Error => log.log("❌ Error logging only"),
     }
 
-    // Check color mode
-    let use_colors = match cli.color {
-        ColorMode::Always => true,
-        ColorMode::Never => false,
-        ColorMode::Auto => atty::is(atty::Stream::Stdout),
+    // Check color mode (NO_COLOR counts only when set and non-empty)
+    let color = if cli.force_color {
+        ColorMode::Always
+    } else {
+        cli.color
     };
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let use_colors = color.enabled(no_color, atty::is(atty::Stream::Stdout));
 
     if use_colors {
-        println!("🎨 Color output enabled");
+        log.log("🎨 Color output enabled");
+    }
+
+    let (result, done) = match &cli.command {
+        Some(Command::Query { pointer, file }) => {
+            (query(&cli, pointer, file, &mut log), "✓ Query complete")
+        }
+        None => (convert(&cli, &mut log), "✓ Conversion complete"),
+    };
+    if let Err(e) = result {
+        log.log(format_args!("Error: {}", e));
+        if cli.log_file.is_some() {
+            eprintln!("Error: {}", e);
+        }
+        std::process::exit(1);
     }
 
-    // Process based on format
-    println!("Converting {} to {:?}", cli.input.display(), cli.format);
+    log.log(done);
+}
 
-    match cli.format {
-        Format::Json => {
-            println!("Converting to JSON{}", if cli.pretty { " (pretty)" } else { "" });
-            // JSON conversion logic here
+/// Format of `data` read from `input`: --input-format, then the file
+/// extension, then the content, then --assume-format
+fn detect_format(
+    cli: &Cli,
+    input: &FileOrStdio,
+    data: &str,
+    log: &mut Logger,
+) -> Result<Format, String> {
+    if let Some(format) = cli
+        .io
+        .input_format
+        .or_else(|| input.path().and_then(Format::from_path))
+    {
+        return Ok(format);
+    }
+    match Format::sniff(data) {
+        Some(format) => {
+            log.log(format_args!(
+                "detected {:?} from the content of {}",
+                format, input
+            ));
+            Ok(format)
+        }
+        None => cli.io.assume_format.ok_or_else(|| {
+            format!(
+                "cannot detect the format of {}; pass --input-format or --assume-format",
+                input
+            )
+        }),
+    }
+}
+
+/// Print the value `pointer` selects in `input`
+fn query(cli: &Cli, pointer: &str, input: &FileOrStdio, log: &mut Logger) -> Result<(), String> {
+    if !pointer.is_empty() && !pointer.starts_with('/') {
+        return Err(format!(
+            "invalid JSON pointer `{}`: it must be empty or start with `/`",
+            pointer
+        ));
+    }
+    let (data, _) =
+        read_input(input, &cli.io).map_err(|e| format!("failed to read {}: {}", input, e))?;
+    let input_format = detect_format(cli, input, &data, log)?;
+    let mut value = parse(&data, input_format, cli.io.trim_fields)
+        .map_err(|e| format!("invalid {:?} in {}: {}", input_format, input, e))?;
+    if !cli.preserve_order {
+        sort_keys(&mut value);
+    }
+    let selected = value
+        .pointer(pointer)
+        .ok_or_else(|| format!("`{}` does not resolve in {}", pointer, input))?;
+    let rendered = render(
+        selected,
+        cli.io.format,
+        cli.layout(),
+        cli.yaml_multi_doc,
+        &cli.csv,
+        &cli.proto,
+    )?;
+    write_output(cli, &rendered)
+}
+
+fn convert(cli: &Cli, log: &mut Logger) -> Result<(), String> {
+    // clap requires the input unless a subcommand is given
+    let input = cli.io.input.as_ref().ok_or("no input file given")?;
+    let (data, input_bytes) =
+        read_input(input, &cli.io).map_err(|e| format!("failed to read {}: {}", input, e))?;
+    let input_format = detect_format(cli, input, &data, log)?;
+    let schema = cli.schema.as_deref().map(load_schema).transpose()?;
+
+    // The cache holds one rendered output, which --output-split doesn't use
+    let cache_dir = cli
+        .cache_dir
+        .as_ref()
+        .filter(|_| cli.output_split.is_none());
+    let cached = cache_dir.map(|dir| {
+        let settings = cache_settings(cli, input_format);
+        // Only valid input is cached, so a hit under the same schema
+        // needs no second check
+        let schema = schema
+            .as_ref()
+            .map_or(String::new(), |(s, _)| s.to_string());
+        // An unreadable descriptor fails in render before anything is stored
+        let descriptor = cli
+            .proto
+            .proto_descriptor
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .unwrap_or_default();
+        dir.join(cache_key(&[
+            settings.as_bytes(),
+            schema.as_bytes(),
+            &descriptor,
+            data.as_bytes(),
+        ]))
+    });
+    let hit = cached.as_ref().and_then(|path| std::fs::read(path).ok());
+
+    let rendered = match hit {
+        Some(rendered) => {
+            log.log(format_args!("cache hit for {}", input));
+            check_output_limit(&rendered, cli.io.limit_memory)?;
+            rendered
+        }
+        None => {
+            log.log(format_args!(
+                "Converting {} from {:?} to {:?}",
+                input, input_format, cli.io.format
+            ));
+            let mut value = parse(&data, input_format, cli.io.trim_fields)
+                .map_err(|e| format!("invalid {:?} in {}: {}", input_format, input, e))?;
+            if let Some((_, validator)) = &schema {
+                validate(validator, &value)?;
+            }
+            if cli.canonicalize {
+                canonicalize(&mut value);
+            } else if !cli.preserve_order {
+                sort_keys(&mut value);
+            }
+            if let Some(key) = &cli.dedupe {
+                let key = Some(key.as_str()).filter(|key| !key.is_empty());
+                match dedupe(&mut value, key) {
+                    Some(removed) => log.log(format_args!("removed {} duplicate(s)", removed)),
+                    None => log.log("--dedupe has no effect: input is not an array"),
+                }
+            }
+            if let Some(field) = &cli.sort_by {
+                if !sort_records(&mut value, field, cli.reverse)? {
+                    log.log("--sort-by has no effect: input is not an array");
+                }
+            }
+            if !cli.fields.is_empty() {
+                let missing = project(&mut value, &cli.fields);
+                if !missing.is_empty() {
+                    let message = format!("unknown field path(s): {}", missing.join(", "));
+                    if cli.strict {
+                        return Err(message);
+                    }
+                    log.log(format_args!("warning: {}", message));
+                }
+            }
+            if let Some(dir) = &cli.output_split {
+                let written = write_split(cli, &value, dir, log)?;
+                if cli.measure {
+                    eprintln!("{}", measurement(input_bytes, written));
+                }
+                return Ok(());
+            }
+            let rendered = render(
+                &value,
+                cli.io.format,
+                cli.layout(),
+                cli.yaml_multi_doc,
+                &cli.csv,
+                &cli.proto,
+            )?;
+            check_output_limit(&rendered, cli.io.limit_memory)?;
+            if let Some(path) = &cached {
+                store_cached(path, &rendered)?;
+            }
+            rendered
+        }
+    };
+    write_output(cli, &rendered)?;
+
+    if cli.measure {
+        eprintln!("{}", measurement(input_bytes, rendered.len() as u64));
+    }
+    Ok(())
+}
+
+/// Fail when `rendered` is larger than --limit-memory
+fn check_output_limit(rendered: &[u8], limit: Option<u64>) -> Result<(), String> {
+    match limit {
+        Some(limit) if rendered.len() as u64 > limit => Err(format!(
+            "output is larger than --limit-memory ({} bytes)",
+            limit
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Render each record of the array `value` to its own file in `dir`, named
+/// by --split-template, and return the number of bytes written
+///
+/// Every name is worked out before anything is written, so a collision or
+/// an existing file (without --force) or a bad template leaves `dir`
+/// untouched.
+fn write_split(cli: &Cli, value: &Value, dir: &Path, log: &mut Logger) -> Result<u64, String> {
+    let records = value
+        .as_array()
+        .ok_or("--output-split needs an array of records")?;
+    let mut names = Vec::with_capacity(records.len());
+    let mut seen = HashSet::new();
+    for (i, record) in records.iter().enumerate() {
+        let name = split_file_name(&cli.split_template, record, cli.io.format)
+            .map_err(|e| format!("--split-template: record {}: {}", i, e))?;
+        if !seen.insert(name.clone()) && !cli.force {
+            return Err(format!(
+                "--output-split: more than one record is named `{}`; pass --force to keep the last",
+                name
+            ));
+        }
+        if !cli.force && dir.join(&name).exists() {
+            return Err(format!(
+                "--output-split: {} already exists; pass --force to overwrite it",
+                dir.join(&name).display()
+            ));
+        }
+        names.push(name);
+    }
+
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    let mut written = 0;
+    for (record, name) in records.iter().zip(&names) {
+        // CSV output is a table of records, so give it a one-row table
+        let record = match cli.io.format {
+            Format::Csv => Value::Array(vec![record.clone()]),
+            _ => record.clone(),
+        };
+        let rendered = render(
+            &record,
+            cli.io.format,
+            cli.layout(),
+            false,
+            &cli.csv,
+            &cli.proto,
+        )?;
+        let path = dir.join(name);
+        FileOrStdio::Path(path.clone())
+            .create(false, cli.io.output_mode, cli.io.output_buffer_size)
+            .and_then(|mut w| w.write_all(&rendered).and_then(|_| w.flush()))
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+        written += rendered.len() as u64;
+    }
+    log.log(format_args!(
+        "wrote {} file(s) to {}",
+        records.len(),
+        dir.display()
+    ));
+    Ok(written)
+}
+
+/// Fill in `template` for one record: `{ext}` becomes the format's
+/// extension and any other `{path}` the record's string, number or bool
+/// at that dotted path. The result must be a plain file name.
+fn split_file_name(template: &str, record: &Value, format: Format) -> Result<String, String> {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let end = start + rest[start..].find('}').ok_or("unclosed `{`")?;
+        let field = &rest[start + 1..end];
+        match (field, record.pointer(&json_pointer(field))) {
+            ("ext", _) => name.push_str(format.extension()),
+            (_, Some(Value::String(s))) => name.push_str(s),
+            (_, Some(v @ (Value::Number(_) | Value::Bool(_)))) => name.push_str(&v.to_string()),
+            (_, Some(_)) => {
+                return Err(format!("field `{}` is not a string, number or bool", field))
+            }
+            (_, None) => return Err(format!("no field `{}`", field)),
+        }
+        rest = &rest[end + 1..];
+    }
+    name.push_str(rest);
+
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(format!("`{}` is not a plain file name", name));
+    }
+    Ok(name)
+}
+
+/// Read and compile the --schema file; the parsed schema is kept for the
+/// cache key
+fn load_schema(path: &Path) -> Result<(Value, jsonschema::Validator), String> {
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let format = Format::from_path(path).unwrap_or(Format::Json);
+    let schema = parse(&data, format, false)
+        .map_err(|e| format!("invalid {:?} in {}: {}", format, path.display(), e))?;
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|e| format!("invalid schema {}: {}", path.display(), e))?;
+    Ok((schema, validator))
+}
+
+/// Check `value` against the schema, listing every violation by its JSON
+/// Pointer (empty for the document root)
+fn validate(validator: &jsonschema::Validator, value: &Value) -> Result<(), String> {
+    let errors: Vec<String> = validator
+        .iter_errors(value)
+        .map(|e| {
+            let path = e.instance_path.to_string();
+            let path = if path.is_empty() {
+                "/".to_string()
+            } else {
+                path
+            };
+            format!("  {}: {}", path, e)
+        })
+        .collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "input does not match the schema ({} error(s)):\n{}",
+        errors.len(),
+        errors.join("\n")
+    ))
+}
+
+/// `--measure` report: both sizes and output size as a multiple of input
+fn measurement(input: u64, output: u64) -> String {
+    let ratio = match input {
+        0 => "n/a".to_string(),
+        input => format!("{:.2}x", output as f64 / input as f64),
+    };
+    format!(
+        "input: {} bytes, output: {} bytes, ratio: {}",
+        input, output, ratio
+    )
+}
+
+/// Write `rendered` to --output, or stdout
+fn write_output(cli: &Cli, rendered: &[u8]) -> Result<(), String> {
+    let output = cli.io.output.clone().unwrap_or(FileOrStdio::Stdio);
+    if cli.io.format == Format::Protobuf
+        && output == FileOrStdio::Stdio
+        && !cli.force
+        && atty::is(atty::Stream::Stdout)
+    {
+        return Err(
+            "refusing to write binary protobuf to a terminal; use --output or pass --force"
+                .to_string(),
+        );
+    }
+    output
+        .create(cli.io.append, cli.io.output_mode, cli.io.output_buffer_size)
+        .and_then(|mut w| w.write_all(rendered).and_then(|_| w.flush()))
+        .map_err(|e| format!("failed to write {}: {}", output, e))
+}
+
+/// Sort object keys at every level
+///
+/// serde_json's `preserve_order` feature keeps maps in insertion (input)
+/// order; this restores the sorted order a plain `Value` map would have.
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = std::mem::take(map).into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (key, mut item) in entries {
+                sort_keys(&mut item);
+                map.insert(key, item);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(sort_keys),
+        _ => {}
+    }
+}
+
+/// Put `value` in canonical form: object keys in sorted order, and floats
+/// with no fractional part (`1.0`, `1e2`) as integers
+fn canonicalize(value: &mut Value) {
+    sort_keys(value);
+    integral_floats_to_ints(value);
+}
+
+/// Replace floats with no fractional part by integers, throughout `value`
+fn integral_floats_to_ints(value: &mut Value) {
+    match value {
+        Value::Object(map) => map.values_mut().for_each(integral_floats_to_ints),
+        Value::Array(items) => items.iter_mut().for_each(integral_floats_to_ints),
+        Value::Number(n) if n.is_f64() => {
+            let f = n.as_f64().unwrap_or_default();
+            // Beyond 2^53 not every integer is a float, so leave those alone
+            if f.fract() == 0.0 && f.abs() < 9_007_199_254_740_992.0 {
+                *value = Value::from(f as i64);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Remove repeated elements from an array, keeping first occurrences.
+/// With `key`, objects sharing that field's value are repeats and elements
+/// without the field are always kept. Returns how many were removed, or
+/// `None` if `value` is not an array.
+fn dedupe(value: &mut Value, key: Option<&str>) -> Option<usize> {
+    let items = value.as_array_mut()?;
+    let before = items.len();
+    let mut seen = HashSet::new();
+    items.retain(|item| {
+        let identity = match key {
+            None => Some(item),
+            Some(key) => item.get(key),
+        };
+        // Keys may be in input order (--preserve-order), so sort a copy's
+        // for equal values to serialize identically
+        identity.is_none_or(|identity| {
+            let mut identity = identity.clone();
+            sort_keys(&mut identity);
+            seen.insert(identity.to_string())
+        })
+    });
+    Some(before - items.len())
+}
+
+/// Stable-sort an array of records by the dotted `field`, descending with
+/// `reverse`. Returns `false` if `value` is not an array, and fails when a
+/// record lacks the field.
+fn sort_records(value: &mut Value, field: &str, reverse: bool) -> Result<bool, String> {
+    let Some(records) = value.as_array_mut() else {
+        return Ok(false);
+    };
+    let pointer = json_pointer(field);
+    if let Some(i) = records.iter().position(|r| r.pointer(&pointer).is_none()) {
+        return Err(format!("--sort-by: record {} has no field `{}`", i, field));
+    }
+
+    records.sort_by(|a, b| {
+        // every record was checked for the field above
+        let key = |r: &Value| r.pointer(&pointer).cloned().unwrap_or(Value::Null);
+        let order = compare_values(&key(a), &key(b));
+        if reverse {
+            order.reverse()
+        } else {
+            order
+        }
+    });
+    Ok(true)
+}
+
+/// Total order for sort keys: null < bools < numbers < strings < others,
+/// numbers compared numerically and everything else by its JSON text
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    let rank = |v: &Value| match v {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) | Value::Object(_) => 4,
+    };
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+            a.total_cmp(&b)
+        }
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => rank(a)
+            .cmp(&rank(b))
+            .then_with(|| a.to_string().cmp(&b.to_string())),
+    }
+}
+
+/// Keep only the dotted `paths` of each record: every element of an array,
+/// or `value` itself. Nested paths keep their nesting, so `server.port`
+/// yields `{"server": {"port": ...}}`. Returns the paths some record lacks.
+fn project(value: &mut Value, paths: &[String]) -> Vec<String> {
+    let mut missing = BTreeSet::new();
+    let mut project_record = |record: &mut Value| {
+        let mut projected = Value::Object(serde_json::Map::new());
+        for path in paths {
+            match record.pointer(&json_pointer(path)) {
+                Some(field) => insert_path(&mut projected, path, field.clone()),
+                None => {
+                    missing.insert(path.clone());
+                }
+            }
+        }
+        *record = projected;
+    };
+
+    match value {
+        Value::Array(records) => records.iter_mut().for_each(&mut project_record),
+        record => project_record(record),
+    }
+    missing.into_iter().collect()
+}
+
+/// JSON Pointer (RFC 6901) for a dotted path
+fn json_pointer(path: &str) -> String {
+    path.split('.')
+        .map(|key| format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// Set the dotted `path` in `target`, creating intermediate objects
+fn insert_path(target: &mut Value, path: &str, field: Value) {
+    let (parents, leaf) = match path.rsplit_once('.') {
+        Some((parents, leaf)) => (Some(parents), leaf),
+        None => (None, path),
+    };
+    let mut object = target;
+    for key in parents.into_iter().flat_map(|parents| parents.split('.')) {
+        let Some(map) = object.as_object_mut() else {
+            return;
+        };
+        object = map
+            .entry(key)
+            .or_insert_with(|| Value::Object(serde_json::Map::new()));
+    }
+    if let Some(object) = object.as_object_mut() {
+        object.insert(leaf.to_string(), field);
+    }
+}
+
+/// Every option that changes the rendered output, as part of the cache key
+fn cache_settings(cli: &Cli, input_format: Format) -> String {
+    format!(
+        "{:?}>{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}",
+        input_format,
+        cli.io.trim_fields,
+        cli.io.format,
+        cli.layout(),
+        cli.yaml_multi_doc,
+        cli.canonicalize,
+        cli.preserve_order,
+        cli.dedupe,
+        (&cli.sort_by, cli.reverse),
+        // --strict turns an unknown --fields path into an error
+        (&cli.fields, cli.strict),
+        (cli.csv.csv_delimiter, cli.csv.no_headers),
+        cli.proto.proto_message
+    )
+}
+
+/// Cache file name for the given inputs (FNV-1a, stable across builds)
+fn cache_key(parts: &[&[u8]]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for &byte in part.iter().chain(&[0xff]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
         }
-        Format::Yaml => {
-            println!("Converting to YAML");
-            // YAML conversion logic here
+    }
+    format!("{:016x}", hash)
+}
+
+fn store_cached(path: &Path, rendered: &[u8]) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create cache dir {}: {}", dir.display(), e))?;
+    }
+    std::fs::write(path, rendered)
+        .map_err(|e| format!("failed to write cache {}: {}", path.display(), e))
+}
+
+/// Parse input text into a format-neutral value; `trim_csv` strips the
+/// whitespace around CSV cells
+fn parse(data: &str, format: Format, trim_csv: bool) -> Result<Value, String> {
+    match format {
+        Format::Json => serde_json::from_str(data).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::from_str(data).map_err(|e| e.to_string()),
+        Format::Toml => toml::from_str(data).map_err(|e| e.to_string()),
+        Format::Csv => parse_csv(data, trim_csv),
+        Format::Protobuf => Err("protobuf is only supported as an output format".to_string()),
+    }
+}
+
+/// Each CSV row becomes an object keyed by the header row
+fn parse_csv(data: &str, trim: bool) -> Result<Value, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(if trim {
+            csv::Trim::All
+        } else {
+            csv::Trim::None
+        })
+        .from_reader(data.as_bytes());
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let row = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(key, cell)| (key.to_string(), Value::String(cell.to_string())))
+            .collect();
+        rows.push(Value::Object(row));
+    }
+
+    Ok(Value::Array(rows))
+}
+
+/// How much whitespace text output gets
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Layout {
+    /// Each format's usual layout
+    Default,
+    /// --pretty
+    Pretty,
+    /// --compact
+    Compact,
+}
+
+impl Cli {
+    fn layout(&self) -> Layout {
+        match (self.pretty, self.compact) {
+            (true, _) => Layout::Pretty,
+            (_, true) => Layout::Compact,
+            _ => Layout::Default,
+        }
+    }
+}
+
+fn render(
+    value: &Value,
+    format: Format,
+    layout: Layout,
+    multi_doc: bool,
+    csv: &CsvOpts,
+    proto: &ProtoOpts,
+) -> Result<Vec<u8>, String> {
+    if let (Format::Yaml, true, Value::Array(records)) = (format, multi_doc, value) {
+        let mut out = Vec::new();
+        for record in records {
+            out.extend_from_slice(b"---\n");
+            out.extend(render(record, format, layout, false, csv, proto)?);
         }
-        Format::Toml => {
-            println!("Converting to TOML");
-            // TOML conversion logic here
+        return Ok(out);
+    }
+
+    let text = match format {
+        Format::Json => {
+            let json = if layout == Layout::Pretty {
+                serde_json::to_string_pretty(value)
+            } else {
+                serde_json::to_string(value)
+            };
+            json.map(|json| json + "\n").map_err(|e| e.to_string())
         }
-        Format::Csv => {
-            println!("Converting to CSV");
-            // CSV conversion logic here
+        // JSON is valid YAML, so single-line YAML is flow-style JSON text
+        Format::Yaml if layout == Layout::Compact => serde_json::to_string(value)
+            .map(|yaml| yaml + "\n")
+            .map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+        Format::Toml if layout == Layout::Pretty => {
+            toml::to_string_pretty(value).map_err(|e| e.to_string())
         }
+        Format::Toml => toml::to_string(value).map_err(|e| e.to_string()),
+        Format::Csv => render_csv(value, csv),
+        Format::Protobuf => return render_protobuf(value, proto),
+    };
+    text.map(String::into_bytes)
+}
+
+/// Render an array of objects as CSV, taking the header from the first row
+fn render_csv(value: &Value, opts: &CsvOpts) -> Result<String, String> {
+    let rows = value
+        .as_array()
+        .ok_or("CSV output requires an array of objects")?;
+    let headers: Vec<&String> = match rows.first() {
+        Some(Value::Object(first)) => first.keys().collect(),
+        Some(_) => return Err("CSV output requires an array of objects".to_string()),
+        None => return Ok(String::new()),
+    };
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(opts.csv_delimiter.unwrap_or(b','))
+        .from_writer(Vec::new());
+    if !opts.no_headers {
+        writer.write_record(&headers).map_err(|e| e.to_string())?;
+    }
+    for row in rows {
+        let row = row
+            .as_object()
+            .ok_or("CSV output requires an array of objects")?;
+        let cells = headers.iter().map(|key| match row.get(*key) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+        });
+        writer.write_record(cells).map_err(|e| e.to_string())?;
     }
 
-    println!("✓ Conversion complete");
+    let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+/// Encode an object as one message, or an array as length-delimited
+/// messages (the usual framing for a stream of records)
+fn render_protobuf(value: &Value, opts: &ProtoOpts) -> Result<Vec<u8>, String> {
+    // clap requires both options with --format protobuf
+    let (Some(path), Some(name)) = (&opts.proto_descriptor, &opts.proto_message) else {
+        return Err("--format protobuf needs --proto-descriptor and --proto-message".to_string());
+    };
+    let bytes = std::fs::read(path)
+        .map_err(|e| format!("failed to read descriptor {}: {}", path.display(), e))?;
+    let pool = DescriptorPool::decode(bytes.as_slice())
+        .map_err(|e| format!("invalid descriptor {}: {}", path.display(), e))?;
+    let descriptor = pool
+        .get_message_by_name(name)
+        .ok_or_else(|| format!("message {} is not defined in {}", name, path.display()))?;
+
+    let encode = |record: &Value| {
+        DynamicMessage::deserialize(descriptor.clone(), record)
+            .map_err(|e| format!("record does not match {}: {}", name, e))
+    };
+    match value {
+        Value::Array(records) => {
+            let mut out = Vec::new();
+            for record in records {
+                encode(record)?
+                    .encode_length_delimited(&mut out)
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(out)
+        }
+        record => Ok(encode(record)?.encode_to_vec()),
+    }
 }
 
 // Helper function to check if stdout is a terminal (for color auto-detection)
 mod atty {
     pub enum Stream {
         Stdout,
+        Stderr,
     }
 
-    pub fn is(_stream: Stream) -> bool {
-        // Simple implementation - checks if stdout is a TTY
+    pub fn is(stream: Stream) -> bool {
+        // Simple implementation - checks if the stream is a TTY
         #[cfg(unix)]
         {
-            unsafe { libc::isatty(libc::STDOUT_FILENO) != 0 }
+            let fd = match stream {
+                Stream::Stdout => libc::STDOUT_FILENO,
+                Stream::Stderr => libc::STDERR_FILENO,
+            };
+            unsafe { libc::isatty(fd) != 0 }
         }
 
         #[cfg(not(unix))]
@@ -136,8 +1713,195 @@ mod atty {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::try_parse_from(["converter"].iter().chain(args)).unwrap()
+    }
+
+    /// A fresh directory for one test's files
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("converter-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Run `convert` with `args`, logging to a file in `dir`
+    fn run_convert(dir: &Path, args: &[&str]) -> Result<(), String> {
+        let log = dir.join("log.txt");
+        let cli = cli(&[args, &["--log-file", log.to_str().unwrap()]].concat());
+        convert(&cli, &mut Logger::new(&cli).unwrap())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_mode_applies_to_existing_files() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = scratch_dir("output-mode");
+        let path = dir.join("out.json");
+        std::fs::write(&path, "{}").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let output = FileOrStdio::Path(path.clone());
+        output.create(false, None, MIN_OUTPUT_BUFFER).unwrap();
+        assert_eq!(mode(&path), 0o644);
+        output
+            .create(false, Some(0o600), MIN_OUTPUT_BUFFER)
+            .unwrap();
+        assert_eq!(mode(&path), 0o600);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn limit_memory_applies_to_cache_hits() {
+        let dir = scratch_dir("limit-memory");
+        let input = dir.join("in.json");
+        // 30 bytes in, 36 out with --pretty
+        std::fs::write(&input, r#"{"name":"a long enough value"}"#).unwrap();
+        let (input, cache) = (input.to_str().unwrap(), dir.join("cache"));
+        let out = dir.join("out.json");
+        let args = [
+            input,
+            "-f",
+            "json",
+            "--pretty",
+            "--cache-dir",
+            cache.to_str().unwrap(),
+            "-o",
+            out.to_str().unwrap(),
+        ];
+        run_convert(&dir, &args).unwrap();
+        let limited = run_convert(&dir, &[&args[..], &["--limit-memory", "32"]].concat());
+        assert_eq!(
+            limited.unwrap_err(),
+            "output is larger than --limit-memory (32 bytes)"
+        );
+        let log = std::fs::read_to_string(dir.join("log.txt")).unwrap();
+        assert!(log.contains("cache hit"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn output_split_keeps_existing_files_without_force() {
+        let dir = scratch_dir("output-split");
+        let input = dir.join("in.json");
+        std::fs::write(&input, r#"[{"id": "a"}, {"id": "b"}]"#).unwrap();
+        let out = dir.join("out");
+        std::fs::create_dir(&out).unwrap();
+        std::fs::write(out.join("b.json"), "keep").unwrap();
+        let args = [
+            input.to_str().unwrap(),
+            "--output-split",
+            out.to_str().unwrap(),
+        ];
+
+        assert!(run_convert(&dir, &args).is_err());
+        assert_eq!(std::fs::read_to_string(out.join("b.json")).unwrap(), "keep");
+        assert!(!out.join("a.json").exists());
+        run_convert(&dir, &[&args[..], &["--force"]].concat()).unwrap();
+        assert_ne!(std::fs::read_to_string(out.join("b.json")).unwrap(), "keep");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dedupe_ignores_key_order_with_preserve_order() {
+        let dir = scratch_dir("dedupe");
+        let input = dir.join("in.json");
+        std::fs::write(&input, r#"[{"a":1,"b":2},{"b":2,"a":1}]"#).unwrap();
+        let out = dir.join("out.json");
+        let args = [
+            input.to_str().unwrap(),
+            "-f",
+            "json",
+            "--dedupe",
+            "--preserve-order",
+            "-o",
+            out.to_str().unwrap(),
+        ];
+        run_convert(&dir, &args).unwrap();
+        let output: Value = serde_json::from_slice(&std::fs::read(&out).unwrap()).unwrap();
+        assert_eq!(output, serde_json::json!([{"a": 1, "b": 2}]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn canonicalize_sorts_keys_and_integral_floats() {
+        let mut value: Value =
+            serde_json::from_str(r#"{"b":1.0,"a":[{"d":2e0,"c":0.5}]}"#).unwrap();
+        canonicalize(&mut value);
+        assert_eq!(value.to_string(), r#"{"a":[{"c":0.5,"d":2}],"b":1}"#);
+    }
+
+    #[test]
+    fn cache_settings_include_csv_options() {
+        let plain = cache_settings(&cli(&["in.json", "-f", "csv"]), Format::Json);
+        let semicolon = cli(&["in.json", "-f", "csv", "--csv-delimiter", ";"]);
+        let headerless = cli(&["in.json", "-f", "csv", "--no-headers"]);
+        assert_ne!(plain, cache_settings(&semicolon, Format::Json));
+        assert_ne!(plain, cache_settings(&headerless, Format::Json));
+    }
+
+    #[test]
+    fn cache_settings_include_strict() {
+        let lenient = cli(&["in.json", "--fields", "id"]);
+        let strict = cli(&["in.json", "--fields", "id", "--strict"]);
+        assert_ne!(
+            cache_settings(&lenient, Format::Json),
+            cache_settings(&strict, Format::Json)
+        );
+    }
+
+    #[test]
+    fn cache_settings_include_proto_message() {
+        let proto = |message| {
+            let args = ["in.json", "-f", "protobuf", "--proto-descriptor", "shop.pb"];
+            let cli = cli(&[&args[..], &["--proto-message", message]].concat());
+            cache_settings(&cli, Format::Json)
+        };
+        assert_ne!(proto("shop.Order"), proto("shop.Refund"));
+    }
+}
+
 // Example usage:
 //
 // cargo run -- input.txt --format json --log-level debug
 // cargo run -- data.yml --format toml --color always --pretty
+// cargo run -- config.toml --format yaml --compact       (one line of YAML)
+// cargo run -- events.json --format yaml --yaml-multi-doc   (a --- document per event)
 // cargo run -- config.json --format yaml --log-level warn
+// cargo run -- config.json --format toml --output config.toml
+// cat config.json | cargo run -- - --format yaml --output -
+// cargo run -- more.json --output all.json --append
+// cargo run -- data.json --force-color | tee ci.log
+// cargo run -- mislabeled.txt --input-format json --format yaml
+// curl -s https://example.com/api | cargo run -- - --format yaml   (JSON sniffed)
+// cat rows | cargo run -- - --assume-format csv                     (CSV can't be sniffed)
+// cargo run -- big.json --format yaml --cache-dir .cache   (second run: cache hit)
+// cargo run -- data.json --format yaml --log-file convert.log --time-format epoch
+// cargo run -- data.json --log-file convert.log --log-max-size 1MiB --log-keep 3
+// cargo run -- rows.json --format csv --csv-delimiter ';' --no-headers
+// cargo run -- rows.json --format json --csv-delimiter ';' --strict   (error)
+// cargo run -- config.yaml --format json --schema config.schema.json
+// cargo run -- users.json --output-split out/ --split-template '{name}.{ext}' --format yaml
+// cargo run -- padded.csv --format json --trim-fields   (" a , b " reads as "a", "b")
+// cargo run -- records.json --range 0-99 --format yaml
+// cargo run -- log.json --range 1000- --input-format json
+// cargo run -- export.csv --input-encoding utf16le --format json
+// cargo run -- legacy.csv --input-encoding latin1 --format yaml
+// cargo run -- huge.json --format yaml --output out.yaml --output-buffer-size 4MiB
+// cargo run -- secrets.yaml --format json --output secrets.json --output-mode 600
+// cargo run -- upload.json --format yaml --limit-memory 64MiB   (error if larger)
+// cargo run -- events.json --dedupe --format yaml
+// cargo run -- big.json --format yaml --measure --output big.yaml
+// cargo run -- a.json --canonicalize > a.out; cargo run -- b.json --canonicalize > b.out
+// cargo run -- config.yaml --format json --preserve-order   (keys as written, not sorted)
+// cargo run -- users.csv --dedupe=id --format json
+// cargo run -- services.json --fields name,server.port --format yaml
+// cargo run -- services.json --sort-by server.port --reverse
+// cargo run -- --format yaml query /server/port config.toml
+// cargo run -- orders.json --format protobuf --proto-descriptor shop.desc \
+//     --proto-message shop.Order --output orders.bin